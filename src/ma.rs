@@ -1,10 +1,79 @@
+extern crate annotate_snippets;
 extern crate regex;
 
+use annotate_snippets::display_list::{DisplayList, FormatOptions};
+use annotate_snippets::snippet::{AnnotationType, Slice, Snippet, SourceAnnotation};
 use regex::Regex;
 use std::collections::HashMap;
-use std::error::Error;
 use std::fs;
-use std::str::FromStr;
+use std::io::{self, Write};
+
+/// Address the first assembled `.text` word is placed at.
+pub const BASE_ADDR: u32 = 0xbfc00000;
+/// Address the `.data` segment starts at, before any `.org`.
+pub const DATA_ADDR: u32 = 0x1001_0000;
+
+/// Which segment a line of assembly contributes to, switched with the
+/// `.text`/`.data` directives. Each segment keeps its own running address,
+/// so a label defined in `.data` resolves to a data address rather than
+/// wherever `.text` happened to be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Segment {
+    Text,
+    Data,
+}
+
+// Assembler directives: `.text`/`.data` switch segment, `.org` sets the
+// current segment's address, and the rest emit raw bytes into it.
+#[derive(Debug, Clone)]
+enum Directive {
+    Text,
+    Data,
+    Org,
+    Word,
+    Half,
+    Byte,
+    Space,
+    Asciiz,
+}
+
+// The running address of both segments, plus which one is currently active.
+// `build_symbol_table` and `tokenize` each carry one of these instead of
+// three loose locals, so `process_directive` only has to thread a single
+// mutable reference through.
+struct Layout {
+    segment: Segment,
+    text_addr: u32,
+    data_addr: u32,
+}
+
+impl Layout {
+    fn new() -> Self {
+        Layout {
+            segment: Segment::Text,
+            text_addr: BASE_ADDR,
+            data_addr: DATA_ADDR,
+        }
+    }
+
+    fn addr(&self) -> u32 {
+        match self.segment {
+            Segment::Text => self.text_addr,
+            Segment::Data => self.data_addr,
+        }
+    }
+
+    fn addr_mut(&mut self) -> &mut u32 {
+        match self.segment {
+            Segment::Text => &mut self.text_addr,
+            Segment::Data => &mut self.data_addr,
+        }
+    }
+
+    fn advance(&mut self, n: u32) {
+        *self.addr_mut() += n;
+    }
+}
 
 #[derive(Debug, Clone)]
 enum Ins {
@@ -24,9 +93,35 @@ enum Ins {
     And,
     Or,
     Nor,
+    Xor,
+    Xori,
+    Sll,
+    Srl,
+    Sra,
+    Jal,
+    Jr,
+    Mult,
+    Multu,
+    Div,
+    Divu,
+    Mfhi,
+    Mflo,
     Break,
 }
 
+// Pseudo-instructions accepted by the lexer but never emitted as a `Token`
+// themselves: `expand_pseudo` rewrites each one into the real instructions
+// above before the symbol table is built.
+#[derive(Debug, Clone)]
+enum Pseudo {
+    Li,
+    La,
+    Move,
+    Nop,
+    Blt,
+    Bgt,
+}
+
 fn get_funct(ins: &Ins) -> u8 {
     match ins {
         Ins::Add => 0x20,
@@ -37,6 +132,17 @@ fn get_funct(ins: &Ins) -> u8 {
         Ins::Or => 0x25,
         Ins::Nor => 0x27,
         Ins::Slt => 0x2a,
+        Ins::Xor => 0x26,
+        Ins::Sll => 0x00,
+        Ins::Srl => 0x02,
+        Ins::Sra => 0x03,
+        Ins::Jr => 0x08,
+        Ins::Mult => 0x18,
+        Ins::Multu => 0x19,
+        Ins::Div => 0x1a,
+        Ins::Divu => 0x1b,
+        Ins::Mfhi => 0x10,
+        Ins::Mflo => 0x12,
         _ => {
             println!("Tried to get funct of instruction {:?}", ins);
             0
@@ -48,11 +154,13 @@ fn get_opcode(ins: &Ins) -> u8 {
     match ins {
         Ins::Break => 0,
         Ins::J => 2,
+        Ins::Jal => 3,
 
         Ins::Beq => 0x4,
         Ins::Bne => 0x5,
         Ins::Addi => 0x8,
         Ins::Addiu => 0x9,
+        Ins::Xori => 0xe,
         Ins::Lui => 0xf,
         Ins::Lw => 0x23,
         Ins::Sw => 0x2b,
@@ -62,103 +170,510 @@ fn get_opcode(ins: &Ins) -> u8 {
 }
 
 #[derive(Debug, Clone)]
-enum Lexeme {
+enum LexemeKind {
     Comma,
     R(Ins),
     I(Ins),
     J(Ins),
+    Pseudo(Pseudo),
+    Directive(Directive),
     Register(u8),
     Label(String),
+    // High/low 16 bits of a label's address, produced by `expand_pseudo` for
+    // `la` and resolved against the symbol table in `parse_addr`.
+    LabelHi(String),
+    LabelLo(String),
     Number(u32),
+    // A quoted string literal, e.g. the argument to `.asciiz`.
+    Str(String),
     OpenParen,
     CloseParen,
     Colon,
 }
 
-fn build_symbol_table(source: Vec<Vec<Lexeme>>, start: u32) -> HashMap<String, u32> {
+// A lexeme together with the source position it came from, so a failure deep
+// inside `tokenize_line`/`parse_addr` can still point back at the offending
+// line and column instead of just printing a message with no context.
+#[derive(Debug, Clone)]
+struct Lexeme {
+    kind: LexemeKind,
+    line: usize,
+    start: usize,
+    end: usize,
+}
+
+impl Lexeme {
+    fn new(kind: LexemeKind, line: usize, start: usize, end: usize) -> Self {
+        Lexeme {
+            kind,
+            line,
+            start,
+            end,
+        }
+    }
+
+    // Build a lexeme with no source of its own (one synthesized while
+    // expanding a pseudo-instruction), inheriting the span of whatever
+    // produced it so diagnostics still land somewhere sensible.
+    fn synthetic(kind: LexemeKind, anchor: &Lexeme) -> Self {
+        Lexeme::new(kind, anchor.line, anchor.start, anchor.end)
+    }
+}
+
+/// A single diagnostic produced while assembling a file: the source line and
+/// column of the offending token, a human-readable message, and the source
+/// line itself so it can be rendered with a caret underline.
+#[derive(Debug)]
+pub struct AssembleError {
+    pub line: usize,
+    pub col: usize,
+    pub msg: String,
+    pub src_line: String,
+}
+
+impl AssembleError {
+    /// Render the error as a `annotate_snippets` snippet with a caret
+    /// underline pointing at the offending token.
+    pub fn render(&self) -> String {
+        let snippet = Snippet {
+            title: None,
+            footer: vec![],
+            slices: vec![Slice {
+                source: &self.src_line,
+                line_start: self.line + 1,
+                origin: None,
+                fold: false,
+                annotations: vec![SourceAnnotation {
+                    range: (self.col, self.col + 1),
+                    label: &self.msg,
+                    annotation_type: AnnotationType::Error,
+                }],
+            }],
+            opt: FormatOptions::default(),
+        };
+        DisplayList::from(snippet).to_string()
+    }
+}
+
+fn push_error(
+    errors: &mut Vec<AssembleError>,
+    source_lines: &[String],
+    line: usize,
+    col: usize,
+    msg: String,
+) {
+    errors.push(AssembleError {
+        line,
+        col,
+        msg,
+        src_line: source_lines.get(line).cloned().unwrap_or_default(),
+    });
+}
+
+fn expect_register(
+    line: &mut Vec<Lexeme>,
+    anchor: (usize, usize),
+    source_lines: &[String],
+    errors: &mut Vec<AssembleError>,
+) -> Option<u8> {
+    match line.pop() {
+        Some(Lexeme {
+            kind: LexemeKind::Register(reg),
+            ..
+        }) => Some(reg),
+        Some(other) => {
+            push_error(
+                errors,
+                source_lines,
+                other.line,
+                other.start,
+                format!("expected a register, found {:?}", other.kind),
+            );
+            None
+        }
+        None => {
+            push_error(
+                errors,
+                source_lines,
+                anchor.0,
+                anchor.1,
+                "expected a register".to_string(),
+            );
+            None
+        }
+    }
+}
+
+fn expect_label(
+    line: &mut Vec<Lexeme>,
+    anchor: (usize, usize),
+    source_lines: &[String],
+    errors: &mut Vec<AssembleError>,
+) -> Option<String> {
+    match line.pop() {
+        Some(Lexeme {
+            kind: LexemeKind::Label(label),
+            ..
+        }) => Some(label),
+        Some(other) => {
+            push_error(
+                errors,
+                source_lines,
+                other.line,
+                other.start,
+                format!("expected a label, found {:?}", other.kind),
+            );
+            None
+        }
+        None => {
+            push_error(
+                errors,
+                source_lines,
+                anchor.0,
+                anchor.1,
+                "expected a label".to_string(),
+            );
+            None
+        }
+    }
+}
+
+fn expect_number(
+    line: &mut Vec<Lexeme>,
+    anchor: (usize, usize),
+    source_lines: &[String],
+    errors: &mut Vec<AssembleError>,
+) -> Option<u32> {
+    match line.pop() {
+        Some(Lexeme {
+            kind: LexemeKind::Number(num),
+            ..
+        }) => Some(num),
+        Some(other) => {
+            push_error(
+                errors,
+                source_lines,
+                other.line,
+                other.start,
+                format!("expected a number, found {:?}", other.kind),
+            );
+            None
+        }
+        None => {
+            push_error(
+                errors,
+                source_lines,
+                anchor.0,
+                anchor.1,
+                "expected a number".to_string(),
+            );
+            None
+        }
+    }
+}
+
+fn expect_str(
+    line: &mut Vec<Lexeme>,
+    anchor: (usize, usize),
+    source_lines: &[String],
+    errors: &mut Vec<AssembleError>,
+) -> Option<String> {
+    match line.pop() {
+        Some(Lexeme {
+            kind: LexemeKind::Str(s),
+            ..
+        }) => Some(s),
+        Some(other) => {
+            push_error(
+                errors,
+                source_lines,
+                other.line,
+                other.start,
+                format!("expected a string literal, found {:?}", other.kind),
+            );
+            None
+        }
+        None => {
+            push_error(
+                errors,
+                source_lines,
+                anchor.0,
+                anchor.1,
+                "expected a string literal".to_string(),
+            );
+            None
+        }
+    }
+}
+
+// Read a comma-separated list of numbers, e.g. the operands of `.word`. At
+// least one number is required; a trailing comma with nothing after it is
+// reported the same way a missing first number would be.
+fn parse_number_list(
+    line: &mut Vec<Lexeme>,
+    anchor: (usize, usize),
+    source_lines: &[String],
+    errors: &mut Vec<AssembleError>,
+) -> Vec<u32> {
+    let mut values = Vec::new();
+    match expect_number(line, anchor, source_lines, errors) {
+        Some(value) => values.push(value),
+        None => return values,
+    }
+    while let Some(Lexeme {
+        kind: LexemeKind::Comma,
+        ..
+    }) = line.last()
+    {
+        line.pop();
+        match expect_number(line, anchor, source_lines, errors) {
+            Some(value) => values.push(value),
+            None => break,
+        }
+    }
+    values
+}
+
+// Parse and apply a directive, returning the raw bytes it contributes (if
+// any) so the caller can advance `layout` and, for `tokenize`, place those
+// bytes at the right address. `.text`/`.data`/`.org` only affect `layout`
+// and never produce bytes.
+fn process_directive(
+    directive: &Directive,
+    anchor: (usize, usize),
+    line: &mut Vec<Lexeme>,
+    layout: &mut Layout,
+    source_lines: &[String],
+    errors: &mut Vec<AssembleError>,
+) -> Option<Vec<u8>> {
+    match directive {
+        Directive::Text => {
+            layout.segment = Segment::Text;
+            None
+        }
+        Directive::Data => {
+            layout.segment = Segment::Data;
+            None
+        }
+        Directive::Org => {
+            if let Some(value) = expect_number(line, anchor, source_lines, errors) {
+                *layout.addr_mut() = value;
+            }
+            None
+        }
+        Directive::Word => Some(
+            parse_number_list(line, anchor, source_lines, errors)
+                .into_iter()
+                .flat_map(|value| value.to_be_bytes())
+                .collect(),
+        ),
+        Directive::Half => Some(
+            parse_number_list(line, anchor, source_lines, errors)
+                .into_iter()
+                .flat_map(|value| (value as u16).to_be_bytes())
+                .collect(),
+        ),
+        Directive::Byte => Some(
+            parse_number_list(line, anchor, source_lines, errors)
+                .into_iter()
+                .map(|value| value as u8)
+                .collect(),
+        ),
+        Directive::Space => expect_number(line, anchor, source_lines, errors)
+            .map(|size| vec![0u8; size as usize]),
+        Directive::Asciiz => expect_str(line, anchor, source_lines, errors).map(|s| {
+            let mut bytes = s.into_bytes();
+            bytes.push(0);
+            bytes
+        }),
+    }
+}
+
+// Mirrors `tokenize`'s segment/address bookkeeping exactly (but only sizes
+// directives instead of emitting their bytes), so a label's address is the
+// same whether it's looked up here or resolved while tokenizing.
+fn build_symbol_table(
+    source: Vec<Vec<Lexeme>>,
+    source_lines: &[String],
+    errors: &mut Vec<AssembleError>,
+) -> HashMap<String, u32> {
     let mut symbol_table = HashMap::new();
-    let mut addr: u32 = start;
+    let mut layout = Layout::new();
+
     for mut line in source {
-        addr += match line.pop() {
-            Some(Lexeme::Label(label)) => {
-                symbol_table.insert(label.clone(), addr >> 2);
-                0
+        // A label isn't the whole line: `buf: .word 5` still has a directive
+        // (or instruction) to size after it, so consume the label (and its
+        // trailing `:`, if lexed) and keep going instead of moving on.
+        if let Some(Lexeme {
+            kind: LexemeKind::Label(label),
+            ..
+        }) = line.last()
+        {
+            symbol_table.insert(label.clone(), layout.addr());
+            line.pop();
+            if let Some(Lexeme {
+                kind: LexemeKind::Colon,
+                ..
+            }) = line.last()
+            {
+                line.pop();
             }
-            Some(Lexeme::R(_)) | Some(Lexeme::I(_)) | Some(Lexeme::J(_)) => 4,
-            _ => 0,
-        };
+        }
+
+        match line.pop() {
+            Some(Lexeme {
+                kind: LexemeKind::R(_),
+                ..
+            })
+            | Some(Lexeme {
+                kind: LexemeKind::I(_),
+                ..
+            })
+            | Some(Lexeme {
+                kind: LexemeKind::J(_),
+                ..
+            }) => layout.advance(4),
+            Some(Lexeme {
+                kind: LexemeKind::Directive(directive),
+                line: l,
+                end,
+                ..
+            }) => {
+                let at = (l, end);
+                if let Some(bytes) =
+                    process_directive(&directive, at, &mut line, &mut layout, source_lines, errors)
+                {
+                    layout.advance(bytes.len() as u32);
+                }
+            }
+            _ => {}
+        }
     }
     symbol_table
 }
 
-fn lexer(source: String) -> Vec<Vec<Lexeme>> {
+// Parse a numeric literal token: `0x`/`0o`/`0b`-prefixed, plain decimal, or
+// negative decimal. Negative values are computed in `i64` and then truncated
+// to their two's-complement `u32` bit pattern, so e.g. `-4` lexes the same
+// `Number` the rest of the pipeline already expects from a positive literal.
+fn parse_number_literal(text: &str) -> Option<u32> {
+    let (negative, rest) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value: i64 = if let Some(digits) = rest.strip_prefix("0x") {
+        i64::from_str_radix(digits, 16).ok()?
+    } else if let Some(digits) = rest.strip_prefix("0o") {
+        i64::from_str_radix(digits, 8).ok()?
+    } else if let Some(digits) = rest.strip_prefix("0b") {
+        i64::from_str_radix(digits, 2).ok()?
+    } else {
+        rest.parse().ok()?
+    };
+    Some((if negative { -value } else { value }) as u32)
+}
+
+fn lexer(source: &str) -> Vec<Vec<Lexeme>> {
     let mut lexemes: Vec<Vec<Lexeme>> = Vec::new();
-    for line in source.lines() {
+    let matcher = Regex::new(r#"(-?[$a-z0-9]+)|(\.[a-z]+)|("[^"]*")|('.')|,|\(|\)|:"#).unwrap();
+    for (line_nr, line) in source.lines().enumerate() {
         lexemes.push(reverse(
-            Regex::new(r"(-?[$a-z0-9]+)|,|\(|\)|:")
-                .unwrap()
-                .find_iter(&line)
+            matcher
+                .find_iter(line)
                 .map(|expr| {
-                    if let Ok(num) = FromStr::from_str(expr.as_str()) {
-                        Lexeme::Number(num)
+                    let text = expr.as_str();
+                    let kind = if text.starts_with('"') {
+                        LexemeKind::Str(text[1..text.len() - 1].to_string())
+                    } else if text.starts_with('\'') {
+                        LexemeKind::Number(text.as_bytes()[1] as u32)
+                    } else if let Some(num) = parse_number_literal(text) {
+                        LexemeKind::Number(num)
                     } else {
-                        match expr.as_str() {
-                            "," => Lexeme::Comma,
-                            "(" => Lexeme::OpenParen,
-                            ")" => Lexeme::CloseParen,
-                            ":" => Lexeme::Colon,
-                            "add" => Lexeme::R(Ins::Add),
-                            "addu" => Lexeme::R(Ins::Addu),
-                            "sub" => Lexeme::R(Ins::Sub),
-                            "subu" => Lexeme::R(Ins::Subu),
-                            "nor" => Lexeme::R(Ins::Nor),
-                            "or" => Lexeme::R(Ins::Or),
-                            "and" => Lexeme::R(Ins::And),
-                            "slt" => Lexeme::R(Ins::Slt),
-                            "addi" => Lexeme::I(Ins::Addi),
-                            "addiu" => Lexeme::I(Ins::Addiu),
-                            "beq" => Lexeme::I(Ins::Beq),
-                            "bne" => Lexeme::I(Ins::Bne),
-                            "sw" => Lexeme::I(Ins::Sw),
-                            "lw" => Lexeme::I(Ins::Lw),
-                            "lui" => Lexeme::I(Ins::Lui),
-                            "break" => Lexeme::J(Ins::Break),
-                            "j" => Lexeme::J(Ins::J),
-                            "$zero" => Lexeme::Register(0),
-                            "$at" => Lexeme::Register(1),
-                            "$v0" => Lexeme::Register(2),
-                            "$v1" => Lexeme::Register(3),
-                            "$a0" => Lexeme::Register(4),
-                            "$a1" => Lexeme::Register(5),
-                            "$a2" => Lexeme::Register(6),
-                            "$a3" => Lexeme::Register(7),
-                            "$t0" => Lexeme::Register(8),
-                            "$t1" => Lexeme::Register(9),
-                            "$t2" => Lexeme::Register(10),
-                            "$t3" => Lexeme::Register(11),
-                            "$t4" => Lexeme::Register(12),
-                            "$t5" => Lexeme::Register(13),
-                            "$t6" => Lexeme::Register(14),
-                            "$t7" => Lexeme::Register(15),
-                            "$s0" => Lexeme::Register(16),
-                            "$s1" => Lexeme::Register(17),
-                            "$s2" => Lexeme::Register(18),
-                            "$s3" => Lexeme::Register(19),
-                            "$s4" => Lexeme::Register(20),
-                            "$s5" => Lexeme::Register(21),
-                            "$s6" => Lexeme::Register(22),
-                            "$s7" => Lexeme::Register(23),
-                            "$t8" => Lexeme::Register(24),
-                            "$t9" => Lexeme::Register(25),
-                            "$k0" => Lexeme::Register(26),
-                            "$k1" => Lexeme::Register(27),
-                            "$gp" => Lexeme::Register(28),
-                            "$sp" => Lexeme::Register(29),
-                            "$fp" => Lexeme::Register(30),
-                            "$ra" => Lexeme::Register(31),
-                            label => Lexeme::Label(label.to_string()),
+                        match text {
+                            "," => LexemeKind::Comma,
+                            "(" => LexemeKind::OpenParen,
+                            ")" => LexemeKind::CloseParen,
+                            ":" => LexemeKind::Colon,
+                            ".text" => LexemeKind::Directive(Directive::Text),
+                            ".data" => LexemeKind::Directive(Directive::Data),
+                            ".org" => LexemeKind::Directive(Directive::Org),
+                            ".word" => LexemeKind::Directive(Directive::Word),
+                            ".half" => LexemeKind::Directive(Directive::Half),
+                            ".byte" => LexemeKind::Directive(Directive::Byte),
+                            ".space" => LexemeKind::Directive(Directive::Space),
+                            ".asciiz" => LexemeKind::Directive(Directive::Asciiz),
+                            "add" => LexemeKind::R(Ins::Add),
+                            "addu" => LexemeKind::R(Ins::Addu),
+                            "sub" => LexemeKind::R(Ins::Sub),
+                            "subu" => LexemeKind::R(Ins::Subu),
+                            "nor" => LexemeKind::R(Ins::Nor),
+                            "or" => LexemeKind::R(Ins::Or),
+                            "and" => LexemeKind::R(Ins::And),
+                            "xor" => LexemeKind::R(Ins::Xor),
+                            "slt" => LexemeKind::R(Ins::Slt),
+                            "sll" => LexemeKind::R(Ins::Sll),
+                            "srl" => LexemeKind::R(Ins::Srl),
+                            "sra" => LexemeKind::R(Ins::Sra),
+                            "jr" => LexemeKind::R(Ins::Jr),
+                            "mult" => LexemeKind::R(Ins::Mult),
+                            "multu" => LexemeKind::R(Ins::Multu),
+                            "div" => LexemeKind::R(Ins::Div),
+                            "divu" => LexemeKind::R(Ins::Divu),
+                            "mfhi" => LexemeKind::R(Ins::Mfhi),
+                            "mflo" => LexemeKind::R(Ins::Mflo),
+                            "addi" => LexemeKind::I(Ins::Addi),
+                            "addiu" => LexemeKind::I(Ins::Addiu),
+                            "xori" => LexemeKind::I(Ins::Xori),
+                            "beq" => LexemeKind::I(Ins::Beq),
+                            "bne" => LexemeKind::I(Ins::Bne),
+                            "sw" => LexemeKind::I(Ins::Sw),
+                            "lw" => LexemeKind::I(Ins::Lw),
+                            "lui" => LexemeKind::I(Ins::Lui),
+                            "break" => LexemeKind::J(Ins::Break),
+                            "j" => LexemeKind::J(Ins::J),
+                            "jal" => LexemeKind::J(Ins::Jal),
+                            "li" => LexemeKind::Pseudo(Pseudo::Li),
+                            "la" => LexemeKind::Pseudo(Pseudo::La),
+                            "move" => LexemeKind::Pseudo(Pseudo::Move),
+                            "nop" => LexemeKind::Pseudo(Pseudo::Nop),
+                            "blt" => LexemeKind::Pseudo(Pseudo::Blt),
+                            "bgt" => LexemeKind::Pseudo(Pseudo::Bgt),
+                            "$zero" => LexemeKind::Register(0),
+                            "$at" => LexemeKind::Register(1),
+                            "$v0" => LexemeKind::Register(2),
+                            "$v1" => LexemeKind::Register(3),
+                            "$a0" => LexemeKind::Register(4),
+                            "$a1" => LexemeKind::Register(5),
+                            "$a2" => LexemeKind::Register(6),
+                            "$a3" => LexemeKind::Register(7),
+                            "$t0" => LexemeKind::Register(8),
+                            "$t1" => LexemeKind::Register(9),
+                            "$t2" => LexemeKind::Register(10),
+                            "$t3" => LexemeKind::Register(11),
+                            "$t4" => LexemeKind::Register(12),
+                            "$t5" => LexemeKind::Register(13),
+                            "$t6" => LexemeKind::Register(14),
+                            "$t7" => LexemeKind::Register(15),
+                            "$s0" => LexemeKind::Register(16),
+                            "$s1" => LexemeKind::Register(17),
+                            "$s2" => LexemeKind::Register(18),
+                            "$s3" => LexemeKind::Register(19),
+                            "$s4" => LexemeKind::Register(20),
+                            "$s5" => LexemeKind::Register(21),
+                            "$s6" => LexemeKind::Register(22),
+                            "$s7" => LexemeKind::Register(23),
+                            "$t8" => LexemeKind::Register(24),
+                            "$t9" => LexemeKind::Register(25),
+                            "$k0" => LexemeKind::Register(26),
+                            "$k1" => LexemeKind::Register(27),
+                            "$gp" => LexemeKind::Register(28),
+                            "$sp" => LexemeKind::Register(29),
+                            "$fp" => LexemeKind::Register(30),
+                            "$ra" => LexemeKind::Register(31),
+                            label => LexemeKind::Label(label.to_string()),
                         }
-                    }
+                    };
+                    Lexeme::new(kind, line_nr, expr.start(), expr.end())
                 })
                 .collect(),
         ));
@@ -169,60 +684,360 @@ fn lexer(source: String) -> Vec<Vec<Lexeme>> {
         .collect()
 }
 
+// Expand `li $t, value` into the real instruction(s) that load it: a single
+// `addiu` only when the constant fits in a *sign-extended* 16 bits (`addiu`
+// would otherwise load the wrong value), otherwise `lui`+`addiu` with the
+// usual carry fixup so the sign-extended low half still adds up correctly.
+fn expand_li(rt: u8, value: u32, anchor: &Lexeme) -> Vec<Vec<Lexeme>> {
+    if value <= 0x7fff || value >= 0xffff_8000 {
+        vec![reverse(vec![
+            Lexeme::synthetic(LexemeKind::I(Ins::Addiu), anchor),
+            Lexeme::synthetic(LexemeKind::Register(rt), anchor),
+            Lexeme::synthetic(LexemeKind::Comma, anchor),
+            Lexeme::synthetic(LexemeKind::Register(0), anchor),
+            Lexeme::synthetic(LexemeKind::Comma, anchor),
+            Lexeme::synthetic(LexemeKind::Number(value & 0xffff), anchor),
+        ])]
+    } else {
+        let lo = value & 0xffff;
+        let hi = if lo & 0x8000 != 0 {
+            ((value >> 16) + 1) & 0xffff
+        } else {
+            value >> 16
+        };
+        vec![
+            reverse(vec![
+                Lexeme::synthetic(LexemeKind::I(Ins::Lui), anchor),
+                Lexeme::synthetic(LexemeKind::Register(1), anchor),
+                Lexeme::synthetic(LexemeKind::Comma, anchor),
+                Lexeme::synthetic(LexemeKind::Number(hi), anchor),
+            ]),
+            reverse(vec![
+                Lexeme::synthetic(LexemeKind::I(Ins::Addiu), anchor),
+                Lexeme::synthetic(LexemeKind::Register(rt), anchor),
+                Lexeme::synthetic(LexemeKind::Comma, anchor),
+                Lexeme::synthetic(LexemeKind::Register(1), anchor),
+                Lexeme::synthetic(LexemeKind::Comma, anchor),
+                Lexeme::synthetic(LexemeKind::Number(lo), anchor),
+            ]),
+        ]
+    }
+}
+
+// Rewrite the pseudo-instructions (`li`, `la`, `move`, `nop`, `blt`, `bgt`)
+// lexed on a single line into the real instructions they stand for. Runs on
+// every line *before* `build_symbol_table`, since an expansion can grow a
+// single line into two words and so shift every label after it.
+fn expand_pseudo(
+    mut line: Vec<Lexeme>,
+    source_lines: &[String],
+    errors: &mut Vec<AssembleError>,
+) -> Vec<Vec<Lexeme>> {
+    // A label on the same line (`loop: nop`) is set aside and put back in
+    // front of the first expanded line.
+    let mut label = Vec::new();
+    if let Some(Lexeme { kind: LexemeKind::Label(_), .. }) = line.last() {
+        label.extend(line.pop());
+        if let Some(Lexeme { kind: LexemeKind::Colon, .. }) = line.last() {
+            label.extend(line.pop());
+        }
+    }
+    let anchor = match line.last() {
+        Some(lexeme @ Lexeme { kind: LexemeKind::Pseudo(_), .. }) => lexeme.clone(),
+        _ => {
+            line.extend(label.into_iter().rev());
+            return vec![line];
+        }
+    };
+    let pseudo = match anchor.kind {
+        LexemeKind::Pseudo(ref pseudo) => pseudo.clone(),
+        _ => unreachable!(),
+    };
+    line.pop();
+    let at = (anchor.line, anchor.end);
+
+    let mut expanded = match pseudo {
+        Pseudo::Nop => vec![reverse(vec![
+            Lexeme::synthetic(LexemeKind::R(Ins::Sll), &anchor),
+            Lexeme::synthetic(LexemeKind::Register(0), &anchor),
+            Lexeme::synthetic(LexemeKind::Comma, &anchor),
+            Lexeme::synthetic(LexemeKind::Register(0), &anchor),
+            Lexeme::synthetic(LexemeKind::Comma, &anchor),
+            Lexeme::synthetic(LexemeKind::Number(0), &anchor),
+        ])],
+        Pseudo::Move => {
+            let rd = match expect_register(&mut line, at, source_lines, errors) {
+                Some(rd) => rd,
+                None => return vec![],
+            };
+            line.pop();
+            let rt = match expect_register(&mut line, at, source_lines, errors) {
+                Some(rt) => rt,
+                None => return vec![],
+            };
+            vec![reverse(vec![
+                Lexeme::synthetic(LexemeKind::R(Ins::Addu), &anchor),
+                Lexeme::synthetic(LexemeKind::Register(rd), &anchor),
+                Lexeme::synthetic(LexemeKind::Comma, &anchor),
+                Lexeme::synthetic(LexemeKind::Register(0), &anchor),
+                Lexeme::synthetic(LexemeKind::Comma, &anchor),
+                Lexeme::synthetic(LexemeKind::Register(rt), &anchor),
+            ])]
+        }
+        Pseudo::Li => {
+            let rt = match expect_register(&mut line, at, source_lines, errors) {
+                Some(rt) => rt,
+                None => return vec![],
+            };
+            line.pop();
+            let value = match expect_number(&mut line, at, source_lines, errors) {
+                Some(value) => value,
+                None => return vec![],
+            };
+            expand_li(rt, value, &anchor)
+        }
+        Pseudo::La => {
+            let rt = match expect_register(&mut line, at, source_lines, errors) {
+                Some(rt) => rt,
+                None => return vec![],
+            };
+            line.pop();
+            let label = match expect_label(&mut line, at, source_lines, errors) {
+                Some(label) => label,
+                None => return vec![],
+            };
+            vec![
+                reverse(vec![
+                    Lexeme::synthetic(LexemeKind::I(Ins::Lui), &anchor),
+                    Lexeme::synthetic(LexemeKind::Register(1), &anchor),
+                    Lexeme::synthetic(LexemeKind::Comma, &anchor),
+                    Lexeme::synthetic(LexemeKind::LabelHi(label.clone()), &anchor),
+                ]),
+                reverse(vec![
+                    Lexeme::synthetic(LexemeKind::I(Ins::Addiu), &anchor),
+                    Lexeme::synthetic(LexemeKind::Register(rt), &anchor),
+                    Lexeme::synthetic(LexemeKind::Comma, &anchor),
+                    Lexeme::synthetic(LexemeKind::Register(1), &anchor),
+                    Lexeme::synthetic(LexemeKind::Comma, &anchor),
+                    Lexeme::synthetic(LexemeKind::LabelLo(label), &anchor),
+                ]),
+            ]
+        }
+        Pseudo::Blt | Pseudo::Bgt => {
+            let a = match expect_register(&mut line, at, source_lines, errors) {
+                Some(a) => a,
+                None => return vec![],
+            };
+            line.pop();
+            let b = match expect_register(&mut line, at, source_lines, errors) {
+                Some(b) => b,
+                None => return vec![],
+            };
+            line.pop();
+            let label = match expect_label(&mut line, at, source_lines, errors) {
+                Some(label) => label,
+                None => return vec![],
+            };
+            let (s, t) = match pseudo {
+                Pseudo::Blt => (a, b),
+                _ => (b, a),
+            };
+            vec![
+                reverse(vec![
+                    Lexeme::synthetic(LexemeKind::R(Ins::Slt), &anchor),
+                    Lexeme::synthetic(LexemeKind::Register(1), &anchor),
+                    Lexeme::synthetic(LexemeKind::Comma, &anchor),
+                    Lexeme::synthetic(LexemeKind::Register(s), &anchor),
+                    Lexeme::synthetic(LexemeKind::Comma, &anchor),
+                    Lexeme::synthetic(LexemeKind::Register(t), &anchor),
+                ]),
+                reverse(vec![
+                    Lexeme::synthetic(LexemeKind::I(Ins::Bne), &anchor),
+                    Lexeme::synthetic(LexemeKind::Register(1), &anchor),
+                    Lexeme::synthetic(LexemeKind::Comma, &anchor),
+                    Lexeme::synthetic(LexemeKind::Register(0), &anchor),
+                    Lexeme::synthetic(LexemeKind::Comma, &anchor),
+                    Lexeme::synthetic(LexemeKind::Label(label), &anchor),
+                ]),
+            ]
+        }
+    };
+    if let Some(first) = expanded.first_mut() {
+        first.extend(label.into_iter().rev());
+    }
+    expanded
+}
+
+// Every variant carries the address it's placed at, since `.org` and the
+// `.text`/`.data` split mean a token's address is no longer just "4 bytes
+// after the previous one".
 #[derive(Debug)]
 enum Token {
-    R(u8, u8, u8, u8, u8, u8),
-    I(u8, u8, u8, u32),
-    J(u8, u32),
+    R(u32, u8, u8, u8, u8, u8, u8),
+    I(u32, u8, u8, u8, u32),
+    J(u32, u8, u32),
+    Data(u32, Vec<u8>),
 }
 
-fn parse_addr(line: &mut Vec<Lexeme>, symbol_table: &HashMap<String, u32>) -> Option<(u8, u32)> {
+fn parse_addr(
+    line: &mut Vec<Lexeme>,
+    symbol_table: &HashMap<String, u32>,
+    anchor: (usize, usize),
+    source_lines: &[String],
+    errors: &mut Vec<AssembleError>,
+) -> Option<(u8, u32)> {
     match line.pop() {
-        Some(Lexeme::Number(num)) => match line.pop() {
-            Some(Lexeme::OpenParen) => {
-                if let Some(Lexeme::Register(reg)) = line.pop() {
-                    match line.pop() {
-                        Some(Lexeme::CloseParen) => Some((reg, num)),
-                        _ => {
-                            println!("Expected close parenthesis");
-                            None
-                        }
+        Some(Lexeme {
+            kind: LexemeKind::Number(num),
+            line: l,
+            end,
+            ..
+        }) => match line.pop() {
+            Some(Lexeme {
+                kind: LexemeKind::OpenParen,
+                ..
+            }) => match expect_register(line, (l, end), source_lines, errors) {
+                Some(reg) => match line.pop() {
+                    Some(Lexeme {
+                        kind: LexemeKind::CloseParen,
+                        ..
+                    }) => Some((reg, num)),
+                    other => {
+                        let (el, ec) = other.map_or((l, end), |o| (o.line, o.start));
+                        push_error(
+                            errors,
+                            source_lines,
+                            el,
+                            ec,
+                            "expected a closing parenthesis".to_string(),
+                        );
+                        None
                     }
-                } else {
-                    println!("Expected register");
-                    None
-                }
-            }
+                },
+                None => None,
+            },
             None => Some((0, num)),
-            _ => {
-                println!("expected open parenthesis after number for adress mode");
+            Some(other) => {
+                push_error(
+                    errors,
+                    source_lines,
+                    other.line,
+                    other.start,
+                    "expected an opening parenthesis after the number for address mode"
+                        .to_string(),
+                );
                 None
             }
         },
-        Some(Lexeme::OpenParen) => {
-            if let Some(Lexeme::Register(reg)) = line.pop() {
-                match line.pop() {
-                    Some(Lexeme::CloseParen) => Some((reg, 0)),
-                    _ => {
-                        println!("Expected close parenthesis");
-                        None
-                    }
+        Some(Lexeme {
+            kind: LexemeKind::OpenParen,
+            line: l,
+            end,
+            ..
+        }) => match expect_register(line, (l, end), source_lines, errors) {
+            Some(reg) => match line.pop() {
+                Some(Lexeme {
+                    kind: LexemeKind::CloseParen,
+                    ..
+                }) => Some((reg, 0)),
+                other => {
+                    let (el, ec) = other.map_or((l, end), |o| (o.line, o.start));
+                    push_error(
+                        errors,
+                        source_lines,
+                        el,
+                        ec,
+                        "expected a closing parenthesis".to_string(),
+                    );
+                    None
                 }
+            },
+            None => None,
+        },
+        Some(Lexeme {
+            kind: LexemeKind::Label(label),
+            line: l,
+            start,
+            ..
+        }) => {
+            if let Some(addr) = symbol_table.get(&label) {
+                Some((0, addr.clone()))
             } else {
-                println!("Expected register");
+                push_error(
+                    errors,
+                    source_lines,
+                    l,
+                    start,
+                    format!("label `{}` not found in symbol table", label),
+                );
                 None
             }
         }
-        Some(Lexeme::Label(label)) => {
+        Some(Lexeme {
+            kind: LexemeKind::LabelHi(label),
+            line: l,
+            start,
+            ..
+        }) => {
             if let Some(addr) = symbol_table.get(&label) {
-                Some((0, addr.clone()))
+                // Mirrors `expand_li`'s carry fixup: the low half is sign
+                // extended by `addiu`, so if it's negative the high half
+                // lexed here needs to be one higher to compensate.
+                let lo = addr & 0xffff;
+                let hi = if lo & 0x8000 != 0 {
+                    (addr >> 16) + 1
+                } else {
+                    addr >> 16
+                };
+                Some((0, hi & 0xffff))
+            } else {
+                push_error(
+                    errors,
+                    source_lines,
+                    l,
+                    start,
+                    format!("label `{}` not found in symbol table", label),
+                );
+                None
+            }
+        }
+        Some(Lexeme {
+            kind: LexemeKind::LabelLo(label),
+            line: l,
+            start,
+            ..
+        }) => {
+            if let Some(addr) = symbol_table.get(&label) {
+                Some((0, addr & 0xffff))
             } else {
-                println!("Did not find label in symbol table");
+                push_error(
+                    errors,
+                    source_lines,
+                    l,
+                    start,
+                    format!("label `{}` not found in symbol table", label),
+                );
                 None
             }
         }
-        lexeme => {
-            println!("Got {:?} when an adress was expected", lexeme);
+        Some(other) => {
+            push_error(
+                errors,
+                source_lines,
+                other.line,
+                other.start,
+                format!("expected an address, found {:?}", other.kind),
+            );
+            None
+        }
+        None => {
+            push_error(
+                errors,
+                source_lines,
+                anchor.0,
+                anchor.1,
+                "expected an address".to_string(),
+            );
             None
         }
     }
@@ -234,161 +1049,244 @@ fn get_relative_addr(line_nr: u32, addr: u32, bits: u32) -> u32 {
     line_nr & ((1 << bits) - 1)
 }
 
+// Whether `value` fits in the 16-bit immediate field of an I-type
+// instruction, either as an unsigned 0..=0xffff literal or as the
+// two's-complement `u32` a negative literal got turned into.
+fn fits_in_i16(value: u32) -> bool {
+    value <= 0xffff || value >= 0xffff_8000
+}
+
 fn tokenize_line(
     line: &mut Vec<Lexeme>,
     symbol_table: &HashMap<String, u32>,
-    line_nr: u32,
+    addr: u32,
+    source_lines: &[String],
+    errors: &mut Vec<AssembleError>,
 ) -> Option<Token> {
+    let line_nr = addr >> 2;
     match line.pop() {
-        Some(Lexeme::R(ins)) => {
-            let rd = if let Some(Lexeme::Register(val)) = line.pop() {
-                val
-            } else {
-                return None;
-            };
-            line.pop();
-            let rs = if let Some(Lexeme::Register(val)) = line.pop() {
-                val
-            } else {
-                return None;
-            };
-            line.pop();
-            let rt = if let Some(Lexeme::Register(val)) = line.pop() {
-                val
-            } else {
-                return None;
-            };
-            line.pop();
+        Some(Lexeme {
+            kind: LexemeKind::R(ins),
+            line: l,
+            end,
+            ..
+        }) => {
+            use Ins::*;
+            let at = (l, end);
+            match ins {
+                Sll | Srl | Sra => {
+                    let rd = expect_register(line, at, source_lines, errors)?;
+                    line.pop();
+                    let rt = expect_register(line, at, source_lines, errors)?;
+                    line.pop();
+                    let shamt = expect_number(line, at, source_lines, errors)?;
+                    Some(Token::R(addr, 0, 0, rt, rd, shamt as u8, get_funct(&ins)))
+                }
+                Jr => {
+                    let rs = expect_register(line, at, source_lines, errors)?;
+                    Some(Token::R(addr, 0, rs, 0, 0, 0, get_funct(&ins)))
+                }
+                Mult | Multu | Div | Divu => {
+                    let rs = expect_register(line, at, source_lines, errors)?;
+                    line.pop();
+                    let rt = expect_register(line, at, source_lines, errors)?;
+                    Some(Token::R(addr, 0, rs, rt, 0, 0, get_funct(&ins)))
+                }
+                Mfhi | Mflo => {
+                    let rd = expect_register(line, at, source_lines, errors)?;
+                    Some(Token::R(addr, 0, 0, 0, rd, 0, get_funct(&ins)))
+                }
+                _ => {
+                    let rd = expect_register(line, at, source_lines, errors)?;
+                    line.pop();
+                    let rs = expect_register(line, at, source_lines, errors)?;
+                    line.pop();
+                    let rt = expect_register(line, at, source_lines, errors)?;
 
-            Some(Token::R(0, rs, rt, rd, 0, get_funct(&ins)))
+                    Some(Token::R(addr, 0, rs, rt, rd, 0, get_funct(&ins)))
+                }
+            }
         }
-        Some(Lexeme::I(ins)) => {
+        Some(Lexeme {
+            kind: LexemeKind::I(ins),
+            line: l,
+            end,
+            ..
+        }) => {
             use Ins::*;
+            let at = (l, end);
             match ins {
                 Beq | Bne => {
-                    let s = if let Some(Lexeme::Register(val)) = line.pop() {
-                        val
-                    } else {
-                        return None;
-                    };
+                    let s = expect_register(line, at, source_lines, errors)?;
                     line.pop();
-                    let t = if let Some(Lexeme::Register(val)) = line.pop() {
-                        val
-                    } else {
-                        return None;
-                    };
+                    let t = expect_register(line, at, source_lines, errors)?;
                     line.pop();
-                    if let Some((r, o)) = parse_addr(line, symbol_table) {
-                        if r == 0 {
-                            Some(Token::I(
-                                get_opcode(&ins),
-                                s,
-                                t,
-                                get_relative_addr(line_nr, o, 16),
-                            ))
-                        } else {
-                            None
-                        }
+                    let (r, o) = parse_addr(line, symbol_table, at, source_lines, errors)?;
+                    if r == 0 {
+                        Some(Token::I(
+                            addr,
+                            get_opcode(&ins),
+                            s,
+                            t,
+                            get_relative_addr(line_nr, o >> 2, 16),
+                        ))
                     } else {
                         None
                     }
                 }
-                Addi | Addiu => {
-                    let t = if let Some(Lexeme::Register(val)) = line.pop() {
-                        val
-                    } else {
-                        return None;
-                    };
+                Addi => {
+                    let t = expect_register(line, at, source_lines, errors)?;
                     line.pop();
-                    let s = if let Some(Lexeme::Register(val)) = line.pop() {
-                        val
-                    } else {
+                    let s = expect_register(line, at, source_lines, errors)?;
+                    line.pop();
+                    let (r, o) = parse_addr(line, symbol_table, at, source_lines, errors)?;
+                    if r != 0 {
                         return None;
-                    };
+                    }
+                    if !fits_in_i16(o) {
+                        push_error(
+                            errors,
+                            source_lines,
+                            l,
+                            end,
+                            format!("immediate 0x{:x} does not fit in a 16-bit signed field", o),
+                        );
+                        return None;
+                    }
+                    Some(Token::I(addr, get_opcode(&ins), s, t, o & 0xffff))
+                }
+                Addiu | Xori => {
+                    let t = expect_register(line, at, source_lines, errors)?;
                     line.pop();
-                    if let Some((r, o)) = parse_addr(line, symbol_table) {
-                        if r == 0 {
-                            Some(Token::I(get_opcode(&ins), s, t, o))
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
+                    let s = expect_register(line, at, source_lines, errors)?;
+                    line.pop();
+                    let (r, o) = parse_addr(line, symbol_table, at, source_lines, errors)?;
+                    if r != 0 {
+                        return None;
+                    }
+                    if !fits_in_i16(o) {
+                        push_error(
+                            errors,
+                            source_lines,
+                            l,
+                            end,
+                            format!("immediate 0x{:x} does not fit in a 16-bit signed field", o),
+                        );
+                        return None;
                     }
+                    Some(Token::I(addr, get_opcode(&ins), s, t, o & 0xffff))
                 }
                 Lw | Sw => {
-                    let t = if let Some(Lexeme::Register(val)) = line.pop() {
-                        val
-                    } else {
-                        return None;
-                    };
+                    let t = expect_register(line, at, source_lines, errors)?;
                     line.pop();
-                    if let Some((s, o)) = parse_addr(line, symbol_table) {
-                        Some(Token::I(get_opcode(&ins), s, t, o))
-                    } else {
-                        None
+                    let (s, o) = parse_addr(line, symbol_table, at, source_lines, errors)?;
+                    if !fits_in_i16(o) {
+                        push_error(
+                            errors,
+                            source_lines,
+                            l,
+                            end,
+                            format!("immediate 0x{:x} does not fit in a 16-bit signed field", o),
+                        );
+                        return None;
                     }
+                    Some(Token::I(addr, get_opcode(&ins), s, t, o & 0xffff))
                 }
                 Lui => {
-                    let t = if let Some(Lexeme::Register(val)) = line.pop() {
-                        val
-                    } else {
-                        return None;
-                    };
+                    let t = expect_register(line, at, source_lines, errors)?;
                     line.pop();
-                    if let Some((r, i)) = parse_addr(line, symbol_table) {
-                        if r == 0 {
-                            Some(Token::I(get_opcode(&ins), 0, t, i))
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
+                    let (r, i) = parse_addr(line, symbol_table, at, source_lines, errors)?;
+                    if r != 0 {
+                        return None;
+                    }
+                    if !fits_in_i16(i) {
+                        push_error(
+                            errors,
+                            source_lines,
+                            l,
+                            end,
+                            format!("immediate 0x{:x} does not fit in a 16-bit signed field", i),
+                        );
+                        return None;
                     }
+                    Some(Token::I(addr, get_opcode(&ins), 0, t, i & 0xffff))
                 }
                 _ => None,
             }
         }
-        Some(Lexeme::J(Ins::J)) => {
-            if let Some((r, addr)) = parse_addr(line, symbol_table) {
-                if r == 0 {
-                    Some(Token::J(get_opcode(&Ins::J), addr))
-                } else {
-                    None
-                }
+        Some(Lexeme {
+            kind: LexemeKind::J(Ins::J),
+            line: l,
+            end,
+            ..
+        }) => {
+            let (r, target) = parse_addr(line, symbol_table, (l, end), source_lines, errors)?;
+            if r == 0 {
+                Some(Token::J(addr, get_opcode(&Ins::J), target >> 2))
+            } else {
+                None
+            }
+        }
+        Some(Lexeme {
+            kind: LexemeKind::J(Ins::Jal),
+            line: l,
+            end,
+            ..
+        }) => {
+            let (r, target) = parse_addr(line, symbol_table, (l, end), source_lines, errors)?;
+            if r == 0 {
+                Some(Token::J(addr, get_opcode(&Ins::Jal), target >> 2))
             } else {
                 None
             }
         }
-        Some(Lexeme::J(Ins::Break)) => {
+        Some(Lexeme {
+            kind: LexemeKind::J(Ins::Break),
+            line: l,
+            start,
+            ..
+        }) => {
             if line.len() != 0 {
-                println!(
-                    "wrong number of lexemes for break, expected 1, got {}",
-                    line.len()
+                push_error(
+                    errors,
+                    source_lines,
+                    l,
+                    start,
+                    format!(
+                        "wrong number of lexemes for break, expected 1, got {}",
+                        line.len() + 1
+                    ),
                 );
                 None
             } else {
-                Some(Token::J(get_opcode(&Ins::Break), 0xd))
+                Some(Token::J(addr, get_opcode(&Ins::Break), 0xd))
             }
         }
         _lexeme => None,
     }
 }
 
-fn asseble_token(instr: Token) -> u32 {
+fn asseble_token(instr: Token) -> (u32, Vec<u8>) {
     match instr {
-        Token::R(opcode, rs, rt, rd, shamt, funct) => {
-            ((opcode as u32) << 26)
+        Token::R(addr, opcode, rs, rt, rd, shamt, funct) => {
+            let word = ((opcode as u32) << 26)
                 | ((rs as u32) << 21)
                 | ((rt as u32) << 16)
                 | ((rd as u32) << 11)
                 | ((shamt as u32) << 6)
-                | funct as u32
+                | funct as u32;
+            (addr, word.to_be_bytes().to_vec())
+        }
+        Token::I(addr, opcode, rs, rt, immidiate) => {
+            let word =
+                ((opcode as u32) << 26) | ((rs as u32) << 21) | ((rt as u32) << 16) | immidiate;
+            (addr, word.to_be_bytes().to_vec())
         }
-        Token::I(opcode, rs, rt, immidiate) => {
-            ((opcode as u32) << 26) | ((rs as u32) << 21) | ((rt as u32) << 16) | immidiate
+        Token::J(addr, opcode, target) => {
+            let word = ((opcode as u32) << 26) | (target & ((1 << 26) - 1));
+            (addr, word.to_be_bytes().to_vec())
         }
-        Token::J(opcode, addr) => ((opcode as u32) << 26) | (addr & ((1 << 26) - 1)),
+        Token::Data(addr, bytes) => (addr, bytes),
     }
 }
 
@@ -408,39 +1306,403 @@ fn reverse<T>(mut input: Vec<T>) -> Vec<T> {
 fn tokenize(
     lexemes: &mut Vec<Vec<Lexeme>>,
     symbol_table: &HashMap<String, u32>,
-    start: u32,
+    source_lines: &[String],
+    errors: &mut Vec<AssembleError>,
 ) -> Vec<Token> {
     let mut tokens = Vec::new();
-    let mut line_nr = start >> 2;
+    let mut layout = Layout::new();
 
     for line in lexemes {
-        if let Some(token) = tokenize_line(line, symbol_table, line_nr) {
+        // The label itself was already resolved in `build_symbol_table`;
+        // skip past it (and its trailing `:`, if lexed) so a same-line
+        // directive or instruction is still seen below instead of discarded.
+        if let Some(Lexeme {
+            kind: LexemeKind::Label(_),
+            ..
+        }) = line.last()
+        {
+            line.pop();
+            if let Some(Lexeme {
+                kind: LexemeKind::Colon,
+                ..
+            }) = line.last()
+            {
+                line.pop();
+            }
+        }
+
+        if let Some(Lexeme {
+            kind: LexemeKind::Directive(directive),
+            line: l,
+            end,
+            ..
+        }) = line.last().cloned()
+        {
+            line.pop();
+            let at = (l, end);
+            if let Some(bytes) =
+                process_directive(&directive, at, line, &mut layout, source_lines, errors)
+            {
+                let addr = layout.addr();
+                let len = bytes.len() as u32;
+                tokens.push(Token::Data(addr, bytes));
+                layout.advance(len);
+            }
+            continue;
+        }
+
+        let addr = layout.addr();
+        if let Some(token) = tokenize_line(line, symbol_table, addr, source_lines, errors) {
             tokens.push(token);
-            line_nr += 1;
+            layout.advance(4);
         }
     }
 
     tokens
 }
 
-fn asseble(tokens: Vec<Token>) -> Vec<u32> {
+fn asseble(tokens: Vec<Token>) -> Vec<(u32, Vec<u8>)> {
     tokens
         .into_iter()
         .map(|token| asseble_token(token))
         .collect()
 }
 
-pub fn assemble_file(filename: String) -> Result<(), Box<dyn Error>> {
-    let start: u32 = 0xbfc00000;
-    let mut lexemes = lexer(fs::read_to_string(filename)?);
-    let symbol_table = build_symbol_table(lexemes.clone(), start);
-    let tokens = tokenize(&mut lexemes, &symbol_table, start);
-    let instructions = asseble(tokens);
-    let mut line_nr = start;
-    for instruction in instructions.into_iter() {
-        println!("0x{:08x}\t0x{:08x}", line_nr, instruction);
-        line_nr += 4;
+/// Assemble `filename` into its `(address, bytes)` chunks, without deciding
+/// how those chunks get written out; see `emit` for turning the result into
+/// an output format. Each chunk is contiguous in memory, but chunks need not
+/// be contiguous with each other (a `.text`/`.data` split, or an `.org`,
+/// leaves gaps).
+pub fn assemble_file(filename: String) -> Result<Vec<(u32, Vec<u8>)>, Vec<AssembleError>> {
+    let source = fs::read_to_string(&filename).map_err(|err| {
+        vec![AssembleError {
+            line: 0,
+            col: 0,
+            msg: format!("could not read {}: {}", filename, err),
+            src_line: String::new(),
+        }]
+    })?;
+    let source_lines: Vec<String> = source.lines().map(|line| line.to_string()).collect();
+
+    let mut errors = Vec::new();
+    let lexemes = lexer(&source);
+    let mut lexemes: Vec<Vec<Lexeme>> = lexemes
+        .into_iter()
+        .flat_map(|line| expand_pseudo(line, &source_lines, &mut errors))
+        .collect();
+    let symbol_table = build_symbol_table(lexemes.clone(), &source_lines, &mut errors);
+    let tokens = tokenize(&mut lexemes, &symbol_table, &source_lines, &mut errors);
+
+    if !errors.is_empty() {
+        return Err(errors);
     }
 
+    Ok(asseble(tokens))
+}
+
+/// Where `emit` should write the assembled words to.
+#[derive(Debug, Clone, Copy)]
+pub enum OutputFormat {
+    /// The original `0xADDR\t0xWORD` debug listing.
+    Text,
+    /// Raw instruction bytes, in the requested endianness.
+    Raw { little_endian: bool },
+    /// A Logisim-compatible `v2.0 raw` memory image: a header line followed
+    /// by one hex word per line.
+    Logisim,
+    /// Intel HEX records, with an extended-linear-address record whenever a
+    /// chunk's high 16 bits change and a terminating `:00000001FF`.
+    IntelHex,
+}
+
+/// `Raw` and `Logisim` concatenate chunks back-to-back with no address field
+/// of their own, so a gap between chunks (a `.data` segment or an `.org`)
+/// would silently place everything after it at the wrong address. Check for
+/// that before either format writes anything.
+fn require_contiguous(chunks: &[(u32, Vec<u8>)], format_name: &str) -> io::Result<()> {
+    let mut expected = None;
+    for (addr, bytes) in chunks {
+        if let Some(expected_addr) = expected {
+            if *addr != expected_addr {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "{} output requires a contiguous address range, but there is a gap \
+                         before 0x{:08x} (expected 0x{:08x}); use --format text or --format hex \
+                         instead",
+                        format_name, addr, expected_addr
+                    ),
+                ));
+            }
+        }
+        expected = Some(addr + bytes.len() as u32);
+    }
     Ok(())
 }
+
+/// Write assembled `(address, bytes)` chunks out as `fmt`. `Raw` and
+/// `Logisim` just concatenate the chunks in order, so they only make sense
+/// when the assembled program is one contiguous region (no `.data` segment
+/// or `.org` gap); `Text` and `IntelHex` handle arbitrary addresses.
+pub fn emit(chunks: &[(u32, Vec<u8>)], fmt: OutputFormat, out: &mut impl Write) -> io::Result<()> {
+    match fmt {
+        OutputFormat::Text => {
+            for (addr, bytes) in chunks {
+                if bytes.len() == 4 {
+                    let word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                    writeln!(out, "0x{:08x}\t0x{:08x}", addr, word)?;
+                } else {
+                    for (offset, byte) in bytes.iter().enumerate() {
+                        writeln!(out, "0x{:08x}\t0x{:02x}", addr + offset as u32, byte)?;
+                    }
+                }
+            }
+            Ok(())
+        }
+        OutputFormat::Raw { little_endian } => {
+            require_contiguous(chunks, "raw")?;
+            let bytes: Vec<u8> = chunks.iter().flat_map(|(_, b)| b.iter().copied()).collect();
+            if little_endian {
+                for word in bytes.chunks(4) {
+                    let mut word = word.to_vec();
+                    word.reverse();
+                    out.write_all(&word)?;
+                }
+            } else {
+                out.write_all(&bytes)?;
+            }
+            Ok(())
+        }
+        OutputFormat::Logisim => {
+            require_contiguous(chunks, "logisim")?;
+            writeln!(out, "v2.0 raw")?;
+            let bytes: Vec<u8> = chunks.iter().flat_map(|(_, b)| b.iter().copied()).collect();
+            for word in bytes.chunks(4) {
+                let mut padded = [0u8; 4];
+                padded[..word.len()].copy_from_slice(word);
+                writeln!(out, "{:x}", u32::from_be_bytes(padded))?;
+            }
+            Ok(())
+        }
+        OutputFormat::IntelHex => emit_intel_hex(chunks, out),
+    }
+}
+
+fn intel_hex_checksum(bytes: &[u8]) -> u8 {
+    bytes
+        .iter()
+        .fold(0u8, |acc, &byte| acc.wrapping_add(byte))
+        .wrapping_neg()
+}
+
+fn intel_hex_record(
+    record_type: u8,
+    addr: u16,
+    data: &[u8],
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let mut bytes = vec![data.len() as u8, (addr >> 8) as u8, addr as u8, record_type];
+    bytes.extend_from_slice(data);
+
+    write!(out, ":")?;
+    for byte in &bytes {
+        write!(out, "{:02X}", byte)?;
+    }
+    writeln!(out, "{:02X}", intel_hex_checksum(&bytes))
+}
+
+fn emit_intel_hex(chunks: &[(u32, Vec<u8>)], out: &mut impl Write) -> io::Result<()> {
+    // An extended linear address record carries the high 16 bits of the
+    // address, since a data record's AAAA field only carries the low 16 —
+    // re-emit one whenever that high half changes, which happens at least
+    // once between `.text` (0xbfc0....) and `.data` (0x1001....).
+    let mut high = None;
+    for (base, bytes) in chunks {
+        for (record_nr, record) in bytes.chunks(16).enumerate() {
+            let addr = base.wrapping_add((record_nr * 16) as u32);
+            let record_high = (addr >> 16) as u16;
+            if high != Some(record_high) {
+                intel_hex_record(0x04, 0, &record_high.to_be_bytes(), out)?;
+                high = Some(record_high);
+            }
+            intel_hex_record(0x00, addr as u16, record, out)?;
+        }
+    }
+
+    intel_hex_record(0x01, 0, &[], out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Mirrors `assemble_file`'s pipeline, but on an in-memory source string
+    // instead of a file on disk, so tests don't need scratch files.
+    fn assemble_source(source: &str) -> Result<Vec<(u32, Vec<u8>)>, Vec<AssembleError>> {
+        let source_lines: Vec<String> = source.lines().map(|line| line.to_string()).collect();
+        let mut errors = Vec::new();
+        let lexemes = lexer(source);
+        let mut lexemes: Vec<Vec<Lexeme>> = lexemes
+            .into_iter()
+            .flat_map(|line| expand_pseudo(line, &source_lines, &mut errors))
+            .collect();
+        let symbol_table = build_symbol_table(lexemes.clone(), &source_lines, &mut errors);
+        let tokens = tokenize(&mut lexemes, &symbol_table, &source_lines, &mut errors);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(asseble(tokens))
+    }
+
+    // `sll` used to be missing from the lexer's keyword match, so it fell
+    // through to `Label("sll")` and the instruction silently vanished.
+    #[test]
+    fn lexer_recognizes_sll_keyword() {
+        let lines = lexer("sll $t0, $t1, 4");
+        assert!(matches!(
+            lines[0].last().unwrap().kind,
+            LexemeKind::R(Ins::Sll)
+        ));
+    }
+
+    // `nop` expands to `sll $zero, $zero, 0`, whose shamt is read by
+    // `tokenize_line` via `expect_number` (a `Number` lexeme, not a
+    // `Register`) — `expand_pseudo` must build it as such.
+    #[test]
+    fn nop_assembles_to_sll_zero_zero_zero() {
+        let chunks = assemble_source(".text\nnop\n").expect("nop should assemble");
+        assert_eq!(chunks, vec![(BASE_ADDR, vec![0, 0, 0, 0])]);
+    }
+
+    #[test]
+    fn sll_assembles_with_expected_encoding() {
+        let chunks = assemble_source(".text\nsll $t0, $t1, 4\n").expect("sll should assemble");
+        // opcode 0, rs 0, rt $t1=9, rd $t0=8, shamt 4, funct 0
+        let expected = (9u32 << 16) | (8u32 << 11) | (4u32 << 6);
+        assert_eq!(chunks, vec![(BASE_ADDR, expected.to_be_bytes().to_vec())]);
+    }
+
+    fn symbol_table_for(source: &str) -> HashMap<String, u32> {
+        let source_lines: Vec<String> = source.lines().map(|line| line.to_string()).collect();
+        let mut errors = Vec::new();
+        let lexemes: Vec<Vec<Lexeme>> = lexer(source)
+            .into_iter()
+            .flat_map(|line| expand_pseudo(line, &source_lines, &mut errors))
+            .collect();
+        build_symbol_table(lexemes, &source_lines, &mut errors)
+    }
+
+    // Labels used to be stored as `addr >> 2`, which collapsed any two
+    // `.data` labels that weren't 4 bytes apart onto the same address.
+    #[test]
+    fn adjacent_byte_labels_in_data_get_distinct_addresses() {
+        let table = symbol_table_for(".data\na: .byte 1\nb: .byte 2\n");
+        assert_eq!(table["a"], DATA_ADDR);
+        assert_eq!(table["b"], DATA_ADDR + 1);
+    }
+
+    // Directive sizing (`.half`/`.byte`/`.word`) should advance the layout by
+    // exactly as many bytes as each directive writes, with no implicit
+    // alignment padding between differently-sized directives.
+    #[test]
+    fn directive_sizing_advances_by_exact_byte_counts() {
+        let table = symbol_table_for(".data\na: .half 1\nb: .byte 2\nc: .word 3\n");
+        assert_eq!(table["a"], DATA_ADDR);
+        assert_eq!(table["b"], DATA_ADDR + 2);
+        assert_eq!(table["c"], DATA_ADDR + 3);
+    }
+
+    // Known-good vector from the Intel HEX format reference: a data record
+    // for bytes `02 33 7A` at address 0x0030 checksums to 0x1E.
+    #[test]
+    fn intel_hex_checksum_matches_reference_vector() {
+        let record = [0x03, 0x00, 0x30, 0x00, 0x02, 0x33, 0x7A];
+        assert_eq!(intel_hex_checksum(&record), 0x1e);
+    }
+
+    #[test]
+    fn emit_intel_hex_writes_extended_address_and_data_records() {
+        let chunks = vec![(0x30u32, vec![0x02u8, 0x33, 0x7A])];
+        let mut out = Vec::new();
+        emit_intel_hex(&chunks, &mut out).expect("intel hex emit should not fail");
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines, vec![":020000040000FA", ":0300300002337A1E", ":00000001FF"]);
+    }
+
+    #[test]
+    fn parse_number_literal_handles_every_prefix_and_sign() {
+        assert_eq!(parse_number_literal("0x2a"), Some(0x2a));
+        assert_eq!(parse_number_literal("0o52"), Some(0o52));
+        assert_eq!(parse_number_literal("0b101010"), Some(0b101010));
+        assert_eq!(parse_number_literal("42"), Some(42));
+        // Negative literals are computed in i64 then truncated to their
+        // two's-complement u32 bit pattern.
+        assert_eq!(parse_number_literal("-1"), Some(0xffff_ffff));
+        assert_eq!(parse_number_literal("-0x8000"), Some(0xffff_8000));
+    }
+
+    #[test]
+    fn parse_number_literal_rejects_garbage() {
+        assert_eq!(parse_number_literal("0xzz"), None);
+        assert_eq!(parse_number_literal("not_a_number"), None);
+    }
+
+    fn number_in(line: &[Lexeme]) -> u32 {
+        line.iter()
+            .find_map(|lexeme| match lexeme.kind {
+                LexemeKind::Number(n) => Some(n),
+                _ => None,
+            })
+            .expect("line has no Number lexeme")
+    }
+
+    // `addiu`'s immediate is sign-extended, so the single-instruction
+    // shortcut is only correct up to 0x7fff; everything else (including
+    // 0x8000..=0xffff, which `value <= 0xffff` used to let through) must go
+    // through the two-instruction `lui`+`addiu` carry fixup below.
+    #[test]
+    fn expand_li_uses_single_addiu_up_to_0x7fff() {
+        let anchor = Lexeme::new(LexemeKind::Colon, 0, 0, 0);
+        let lines = expand_li(8, 0x7fff, &anchor);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(number_in(&lines[0]), 0x7fff);
+    }
+
+    #[test]
+    fn expand_li_uses_two_instructions_from_0x8000() {
+        let anchor = Lexeme::new(LexemeKind::Colon, 0, 0, 0);
+        let lines = expand_li(8, 0x8000, &anchor);
+        assert_eq!(lines.len(), 2);
+        let hi = number_in(&lines[0]);
+        let lo = number_in(&lines[1]);
+        let lo_sign_extended = (lo as i16) as i32 as u32;
+        assert_eq!((hi << 16).wrapping_add(lo_sign_extended), 0x8000);
+    }
+
+    #[test]
+    fn expand_li_uses_single_addiu_for_negative_values() {
+        let anchor = Lexeme::new(LexemeKind::Colon, 0, 0, 0);
+        let lines = expand_li(8, 0xffff_ffff, &anchor); // -1
+        assert_eq!(lines.len(), 1);
+        assert_eq!(number_in(&lines[0]), 0xffff);
+    }
+
+    #[test]
+    fn expand_li_carry_fixup_round_trips_arbitrary_values() {
+        let anchor = Lexeme::new(LexemeKind::Colon, 0, 0, 0);
+        for value in [0x9234u32, 0xffff, 0x1_0000, 0x7fff_ffff] {
+            let lines = expand_li(8, value, &anchor);
+            assert_eq!(lines.len(), 2, "value 0x{:x} should need two instructions", value);
+            let hi = number_in(&lines[0]);
+            let lo = number_in(&lines[1]);
+            let lo_sign_extended = (lo as i16) as i32 as u32;
+            assert_eq!(
+                (hi << 16).wrapping_add(lo_sign_extended),
+                value,
+                "value 0x{:x}",
+                value
+            );
+        }
+    }
+}
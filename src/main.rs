@@ -1,20 +1,87 @@
 pub mod ma;
 
-use ma::assemble_file;
+use ma::{assemble_file, emit, OutputFormat};
 use std::env;
+use std::fs::File;
+use std::io::{self, Write};
 use std::process;
 
+fn print_usage() {
+    println!(
+        "usage: stupid-mips-assembler <file> [--format text|bin|hex|logisim] \
+         [--little-endian] [--output <path>]"
+    );
+}
+
 fn main() {
     let mut args = env::args();
     args.next();
-    let filename = if let Some(filename) = args.next() {
-        filename
-    } else {
-        println!("Must have filename as argument");
+
+    let mut filename = None;
+    let mut format = OutputFormat::Text;
+    let mut little_endian = false;
+    let mut output = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                let value = args.next().unwrap_or_else(|| {
+                    println!("--format requires a value");
+                    process::exit(1);
+                });
+                format = match value.as_str() {
+                    "text" => OutputFormat::Text,
+                    "bin" => OutputFormat::Raw { little_endian },
+                    "hex" => OutputFormat::IntelHex,
+                    "logisim" => OutputFormat::Logisim,
+                    other => {
+                        println!("unknown output format {}", other);
+                        process::exit(1);
+                    }
+                };
+            }
+            "--little-endian" => {
+                little_endian = true;
+                if let OutputFormat::Raw { little_endian: le } = &mut format {
+                    *le = true;
+                }
+            }
+            "--output" => {
+                output = Some(args.next().unwrap_or_else(|| {
+                    println!("--output requires a value");
+                    process::exit(1);
+                }));
+            }
+            _ if filename.is_none() => filename = Some(arg),
+            _ => {
+                print_usage();
+                process::exit(1);
+            }
+        }
+    }
+
+    let filename = filename.unwrap_or_else(|| {
+        print_usage();
         process::exit(1);
+    });
+
+    let chunks = assemble_file(filename).unwrap_or_else(|errors| {
+        for error in &errors {
+            eprintln!("{}", error.render());
+        }
+        process::exit(1);
+    });
+
+    let mut out: Box<dyn Write> = match &output {
+        Some(path) => Box::new(File::create(path).unwrap_or_else(|err| {
+            eprintln!("could not create {}: {}", path, err);
+            process::exit(1);
+        })),
+        None => Box::new(io::stdout()),
     };
-    assemble_file(filename).unwrap_or_else(|err| {
-        eprintln!("problem when assembling file {}", err);
+
+    emit(&chunks, format, &mut out).unwrap_or_else(|err| {
+        eprintln!("problem writing output: {}", err);
         process::exit(1);
     });
 }